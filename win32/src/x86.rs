@@ -1,8 +1,102 @@
 use std::collections::HashMap;
 
-use anyhow::bail;
+use anyhow::anyhow;
 use tsify::Tsify;
 
+/// Operand width, distinguishing the byte/word/long views of a register or
+/// memory access (as in moa's `Size`).
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Size {
+    Byte,
+    Word,
+    Long,
+}
+impl Size {
+    /// Mask selecting the bits this width occupies.
+    fn mask(self) -> u32 {
+        match self {
+            Size::Byte => 0xff,
+            Size::Word => 0xffff,
+            Size::Long => 0xffff_ffff,
+        }
+    }
+
+    /// Index of the sign bit for this width.
+    fn sign_bit(self) -> u32 {
+        match self {
+            Size::Byte => 7,
+            Size::Word => 15,
+            Size::Long => 31,
+        }
+    }
+}
+
+/// The subset of the x86 EFLAGS status bits that arithmetic and logic ops
+/// compute, together with the rules for deriving them from an operation's
+/// operands and result.
+///
+/// The compute helpers are pure functions of their inputs so the flag logic
+/// can be exercised without going through the decoder.
+struct Flags;
+impl Flags {
+    const CF: u32 = 1 << 0;
+    const PF: u32 = 1 << 2;
+    const ZF: u32 = 1 << 6;
+    const SF: u32 = 1 << 7;
+    const OF: u32 = 1 << 11;
+
+    /// The bits recomputed by every flag-setting op; cleared before the fresh
+    /// value is merged back into EFLAGS.
+    const ARITH: u32 = Self::CF | Self::PF | Self::ZF | Self::SF | Self::OF;
+
+    /// ZF/SF/PF, shared by every op regardless of how CF/OF are derived.
+    fn result(result: u32, size: Size) -> u32 {
+        let value = result & size.mask();
+        let mut flags = 0;
+        if value == 0 {
+            flags |= Self::ZF;
+        }
+        if (value >> size.sign_bit()) & 1 != 0 {
+            flags |= Self::SF;
+        }
+        if (value as u8).count_ones() % 2 == 0 {
+            flags |= Self::PF;
+        }
+        flags
+    }
+
+    /// Flags for `a - b`, plus the wrapped result.
+    fn sub(a: u32, b: u32, size: Size) -> (u32, u32) {
+        let result = a.wrapping_sub(b);
+        let mut flags = Self::result(result, size);
+        if (a & size.mask()) < (b & size.mask()) {
+            flags |= Self::CF;
+        }
+        if (((a ^ b) & (a ^ result)) >> size.sign_bit()) & 1 != 0 {
+            flags |= Self::OF;
+        }
+        (result, flags)
+    }
+
+    /// Flags for `a + b`, plus the wrapped result.
+    fn add(a: u32, b: u32, size: Size) -> (u32, u32) {
+        let result = a.wrapping_add(b);
+        let mut flags = Self::result(result, size);
+        if (result & size.mask()) < (a & size.mask()) {
+            flags |= Self::CF;
+        }
+        if (((a ^ result) & (b ^ result)) >> size.sign_bit()) & 1 != 0 {
+            flags |= Self::OF;
+        }
+        (result, flags)
+    }
+
+    /// Flags for a bitwise op (AND/OR/XOR): CF and OF are cleared.
+    fn logic(result: u32, size: Size) -> u32 {
+        Self::result(result, size)
+    }
+}
+
 #[derive(Tsify)]
 pub struct Registers {
     pub eax: u32,
@@ -23,6 +117,8 @@ pub struct Registers {
     pub fs: u16,
     pub gs: u16,
     pub ss: u16,
+
+    pub eflags: u32,
 }
 impl Registers {
     fn new() -> Self {
@@ -42,206 +138,1196 @@ impl Registers {
             fs: 0,
             gs: 0,
             ss: 0,
+            eflags: 0,
         }
     }
 
-    fn get(&self, name: iced_x86::Register) -> u32 {
-        match name {
-            iced_x86::Register::None => 0,
-            iced_x86::Register::EAX => self.eax,
-            iced_x86::Register::EBX => self.ebx,
-            iced_x86::Register::ECX => self.ecx,
-            iced_x86::Register::EDX => self.edx,
-            iced_x86::Register::ESP => self.esp,
-            iced_x86::Register::EBP => self.ebp,
-            iced_x86::Register::ESI => self.esi,
-            iced_x86::Register::EDI => self.edi,
-            /*            iced_x86::Register::CS => self.cs,
-            iced_x86::Register::DS => self.ds,
-            iced_x86::Register::ES => self.es,
-            iced_x86::Register::FS => self.fs,
-            iced_x86::Register::SS => self.ss,
-            iced_x86::Register::GS => self.gs, */
-            _ => todo!(),
-        }
-    }
-    fn set(&mut self, name: iced_x86::Register, value: u32) {
+    /// Test whether a single status flag (one of the `Flags` masks) is set.
+    fn flag(&self, mask: u32) -> bool {
+        self.eflags & mask != 0
+    }
+
+    /// Read `backing` through a sub-register view: `high` selects the AH-style
+    /// byte, `size` the width; the value is returned zero-extended.
+    fn read_part(backing: u32, size: Size, high: bool) -> u32 {
+        let shift = if high { 8 } else { 0 };
+        (backing >> shift) & size.mask()
+    }
+
+    /// Write the low bits of `value` into the `size`/`high` view of `backing`,
+    /// leaving the surrounding bits untouched.
+    fn write_part(backing: u32, size: Size, high: bool, value: u32) -> u32 {
+        let shift = if high { 8 } else { 0 };
+        let mask = size.mask() << shift;
+        (backing & !mask) | ((value << shift) & mask)
+    }
+
+    fn get(&self, name: iced_x86::Register) -> anyhow::Result<u32> {
+        use iced_x86::Register as R;
+        use Size::*;
+        Ok(match name {
+            R::None => 0,
+
+            R::EAX => self.eax,
+            R::EBX => self.ebx,
+            R::ECX => self.ecx,
+            R::EDX => self.edx,
+            R::ESP => self.esp,
+            R::EBP => self.ebp,
+            R::ESI => self.esi,
+            R::EDI => self.edi,
+
+            R::AX => Self::read_part(self.eax, Word, false),
+            R::BX => Self::read_part(self.ebx, Word, false),
+            R::CX => Self::read_part(self.ecx, Word, false),
+            R::DX => Self::read_part(self.edx, Word, false),
+            R::SP => Self::read_part(self.esp, Word, false),
+            R::BP => Self::read_part(self.ebp, Word, false),
+            R::SI => Self::read_part(self.esi, Word, false),
+            R::DI => Self::read_part(self.edi, Word, false),
+
+            R::AL => Self::read_part(self.eax, Byte, false),
+            R::BL => Self::read_part(self.ebx, Byte, false),
+            R::CL => Self::read_part(self.ecx, Byte, false),
+            R::DL => Self::read_part(self.edx, Byte, false),
+            R::AH => Self::read_part(self.eax, Byte, true),
+            R::BH => Self::read_part(self.ebx, Byte, true),
+            R::CH => Self::read_part(self.ecx, Byte, true),
+            R::DH => Self::read_part(self.edx, Byte, true),
+
+            R::CS => self.cs as u32,
+            R::DS => self.ds as u32,
+            R::ES => self.es as u32,
+            R::FS => self.fs as u32,
+            R::GS => self.gs as u32,
+            R::SS => self.ss as u32,
+
+            _ => {
+                return Err(Fault {
+                    kind: FaultKind::IllegalInstruction,
+                    eip: self.eip,
+                }
+                .into())
+            }
+        })
+    }
+    fn set(&mut self, name: iced_x86::Register, value: u32) -> anyhow::Result<()> {
+        use iced_x86::Register as R;
+        use Size::*;
         match name {
-            iced_x86::Register::EAX => self.eax = value,
-            iced_x86::Register::EBX => self.ebx = value,
-            iced_x86::Register::ECX => self.ecx = value,
-            iced_x86::Register::EDX => self.edx = value,
-            iced_x86::Register::ESP => self.esp = value,
-            iced_x86::Register::EBP => self.ebp = value,
-            iced_x86::Register::ESI => self.esi = value,
-            iced_x86::Register::EDI => self.edi = value,
-            /*            iced_x86::Register::CS => self.cs,
-            iced_x86::Register::DS => self.ds,
-            iced_x86::Register::ES => self.es,
-            iced_x86::Register::FS => self.fs,
-            iced_x86::Register::SS => self.ss,
-            iced_x86::Register::GS => self.gs, */
-            _ => todo!(),
+            R::EAX => self.eax = value,
+            R::EBX => self.ebx = value,
+            R::ECX => self.ecx = value,
+            R::EDX => self.edx = value,
+            R::ESP => self.esp = value,
+            R::EBP => self.ebp = value,
+            R::ESI => self.esi = value,
+            R::EDI => self.edi = value,
+
+            R::AX => self.eax = Self::write_part(self.eax, Word, false, value),
+            R::BX => self.ebx = Self::write_part(self.ebx, Word, false, value),
+            R::CX => self.ecx = Self::write_part(self.ecx, Word, false, value),
+            R::DX => self.edx = Self::write_part(self.edx, Word, false, value),
+            R::SP => self.esp = Self::write_part(self.esp, Word, false, value),
+            R::BP => self.ebp = Self::write_part(self.ebp, Word, false, value),
+            R::SI => self.esi = Self::write_part(self.esi, Word, false, value),
+            R::DI => self.edi = Self::write_part(self.edi, Word, false, value),
+
+            R::AL => self.eax = Self::write_part(self.eax, Byte, false, value),
+            R::BL => self.ebx = Self::write_part(self.ebx, Byte, false, value),
+            R::CL => self.ecx = Self::write_part(self.ecx, Byte, false, value),
+            R::DL => self.edx = Self::write_part(self.edx, Byte, false, value),
+            R::AH => self.eax = Self::write_part(self.eax, Byte, true, value),
+            R::BH => self.ebx = Self::write_part(self.ebx, Byte, true, value),
+            R::CH => self.ecx = Self::write_part(self.ecx, Byte, true, value),
+            R::DH => self.edx = Self::write_part(self.edx, Byte, true, value),
+
+            R::CS => self.cs = value as u16,
+            R::DS => self.ds = value as u16,
+            R::ES => self.es = value as u16,
+            R::FS => self.fs = value as u16,
+            R::GS => self.gs = value as u16,
+            R::SS => self.ss = value as u16,
+
+            _ => {
+                return Err(Fault {
+                    kind: FaultKind::IllegalInstruction,
+                    eip: self.eip,
+                }
+                .into())
+            }
         }
+        Ok(())
     }
 }
 
+/// A byte-addressable device mapped into the address space. Addresses handed to
+/// the methods are region-relative (already offset from the region's base).
+///
+/// Little-endian `u16`/`u32` accessors default to composing `u8` accesses so a
+/// device only has to implement the byte methods; `Ram` uses these defaults too.
+pub trait Mem {
+    fn read_u8(&self, addr: u32) -> anyhow::Result<u8>;
+    fn write_u8(&mut self, addr: u32, value: u8) -> anyhow::Result<()>;
+
+    fn read_u16(&self, addr: u32) -> anyhow::Result<u16> {
+        Ok((self.read_u8(addr)? as u16) | ((self.read_u8(addr + 1)? as u16) << 8))
+    }
+    fn read_u32(&self, addr: u32) -> anyhow::Result<u32> {
+        Ok((self.read_u16(addr)? as u32) | ((self.read_u16(addr + 2)? as u32) << 16))
+    }
+    fn write_u16(&mut self, addr: u32, value: u16) -> anyhow::Result<()> {
+        self.write_u8(addr, value as u8)?;
+        self.write_u8(addr + 1, (value >> 8) as u8)
+    }
+    fn write_u32(&mut self, addr: u32, value: u32) -> anyhow::Result<()> {
+        self.write_u16(addr, value as u16)?;
+        self.write_u16(addr + 2, (value >> 16) as u16)
+    }
+
+    /// The contiguous backing bytes, if this device is plain RAM. Used by the
+    /// decoder, which needs a slice to read ahead; MMIO regions return `None`.
+    fn bytes(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// Flat RAM backing a region of the address space.
+pub struct Ram(pub Vec<u8>);
+impl Mem for Ram {
+    fn read_u8(&self, addr: u32) -> anyhow::Result<u8> {
+        self.0
+            .get(addr as usize)
+            .copied()
+            .ok_or_else(|| anyhow!("out-of-bounds read at {:#x}", addr))
+    }
+    fn write_u8(&mut self, addr: u32, value: u8) -> anyhow::Result<()> {
+        *self
+            .0
+            .get_mut(addr as usize)
+            .ok_or_else(|| anyhow!("out-of-bounds write at {:#x}", addr))? = value;
+        Ok(())
+    }
+    fn bytes(&self) -> Option<&[u8]> {
+        Some(&self.0)
+    }
+}
+
+struct Region {
+    name: String,
+    base: u32,
+    len: u32,
+    mem: Box<dyn Mem>,
+}
+
+/// The guest address space: a set of named regions, each mapped to a disjoint
+/// `[base, base + len)` range, with accesses dispatched by address. An access
+/// outside every region is a recoverable error rather than a panic (to be
+/// surfaced as a #PF later).
+pub struct Bus {
+    regions: Vec<Region>,
+}
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            regions: Vec::new(),
+        }
+    }
+
+    /// Map `mem` into `[base, base + len)` under `name`.
+    pub fn mount(&mut self, name: impl Into<String>, base: u32, len: u32, mem: Box<dyn Mem>) {
+        self.regions.push(Region {
+            name: name.into(),
+            base,
+            len,
+            mem,
+        });
+    }
+
+    fn region(&self, addr: u32) -> anyhow::Result<&Region> {
+        self.regions
+            .iter()
+            .find(|r| addr >= r.base && addr - r.base < r.len)
+            .ok_or_else(|| anyhow!("unmapped memory access at {:#x}", addr))
+    }
+    fn region_mut(&mut self, addr: u32) -> anyhow::Result<&mut Region> {
+        self.regions
+            .iter_mut()
+            .find(|r| addr >= r.base && addr - r.base < r.len)
+            .ok_or_else(|| anyhow!("unmapped memory access at {:#x}", addr))
+    }
+
+    pub fn read_u8(&self, addr: u32) -> anyhow::Result<u8> {
+        let r = self.region(addr)?;
+        r.mem.read_u8(addr - r.base)
+    }
+    pub fn read_u16(&self, addr: u32) -> anyhow::Result<u16> {
+        let r = self.region(addr)?;
+        r.mem.read_u16(addr - r.base)
+    }
+    pub fn read_u32(&self, addr: u32) -> anyhow::Result<u32> {
+        let r = self.region(addr)?;
+        r.mem.read_u32(addr - r.base)
+    }
+    pub fn write_u8(&mut self, addr: u32, value: u8) -> anyhow::Result<()> {
+        let r = self.region_mut(addr)?;
+        r.mem.write_u8(addr - r.base, value)
+    }
+    pub fn write_u16(&mut self, addr: u32, value: u16) -> anyhow::Result<()> {
+        let r = self.region_mut(addr)?;
+        r.mem.write_u16(addr - r.base, value)
+    }
+    pub fn write_u32(&mut self, addr: u32, value: u32) -> anyhow::Result<()> {
+        let r = self.region_mut(addr)?;
+        r.mem.write_u32(addr - r.base, value)
+    }
+
+    /// Borrow the backing bytes of the region containing `addr`, starting at
+    /// `addr`, for the instruction decoder. Errors if `addr` is unmapped or
+    /// falls in a region that is not plain RAM.
+    fn code(&self, addr: u32) -> anyhow::Result<&[u8]> {
+        let r = self.region(addr)?;
+        let bytes = r
+            .mem
+            .bytes()
+            .ok_or_else(|| anyhow!("cannot execute from region {:?}", r.name))?;
+        Ok(&bytes[(addr - r.base) as usize..])
+    }
+}
+impl Default for Bus {
+    fn default() -> Self {
+        Bus::new()
+    }
+}
+
+/// Execution state of the CPU.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Reset; no instruction has executed yet.
+    Init,
+    /// Actively fetching and executing.
+    Running,
+    /// Stopped after an unrecovered fault; `step` is a no-op until reset.
+    Halted,
+}
+
+/// The processor faults `run` can raise, mirroring the native exception vectors.
+#[derive(Clone, Copy, Debug)]
+pub enum FaultKind {
+    IllegalInstruction,
+    UnmappedMemory,
+    /// An unimplemented imported function was called; carries its target address.
+    UnimplementedImport(u32),
+}
+
+/// A processor fault carrying the kind and the EIP of the faulting instruction.
+/// Travels through the usual `anyhow` error channel and can be recovered by
+/// downcast at the dispatch boundary.
+#[derive(Clone, Copy, Debug)]
+pub struct Fault {
+    pub kind: FaultKind,
+    pub eip: u32,
+}
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?} at {:#x}", self.kind, self.eip)
+    }
+}
+impl std::error::Error for Fault {}
+
+/// Build an unmapped-memory fault anchored at `eip`, as an `anyhow::Error`.
+fn unmapped_at(eip: u32) -> anyhow::Error {
+    Fault {
+        kind: FaultKind::UnmappedMemory,
+        eip,
+    }
+    .into()
+}
+
 pub struct X86 {
-    pub mem: Vec<u8>,
+    pub mem: Bus,
     pub regs: Registers,
     // XXX PE base address, needed for winapi impls; we'll need some win32 system state bit.
     pub base: u32,
     pub imports: HashMap<u32, Option<fn(&mut X86)>>,
+    pub state: State,
+    /// Decoded instructions memoized by their guest EIP, so the decoder is only
+    /// constructed once per address rather than on every `step`.
+    decode_cache: HashMap<u32, iced_x86::Instruction>,
+    /// Compiled host-code blocks, keyed by the guest EIP they start at.
+    #[cfg(feature = "jit")]
+    jit: jit::JitCache,
 }
 impl X86 {
     pub fn new() -> Self {
         X86 {
-            mem: Vec::new(),
+            mem: Bus::new(),
             regs: Registers::new(),
             base: 0,
             imports: HashMap::new(),
+            state: State::Init,
+            decode_cache: HashMap::new(),
+            #[cfg(feature = "jit")]
+            jit: jit::JitCache::new(),
+        }
+    }
+
+    /// Deliver `fault` to the CPU's exception handler. The default behaviour
+    /// halts the core; a richer implementation could look up a vector and set
+    /// `eip` to transfer control (the eventual #PF path).
+    fn dispatch(&mut self, fault: Fault) -> anyhow::Result<()> {
+        self.state = State::Halted;
+        Err(fault.into())
+    }
+
+    /// Drop any cached decoded instructions overlapping `[addr, addr + len)` so
+    /// that self-modifying code re-decodes the bytes that were just written.
+    pub fn invalidate(&mut self, addr: u32, len: u32) {
+        let end = addr + len;
+        self.decode_cache
+            .retain(|&eip, instr| eip + instr.len() as u32 <= addr || eip >= end);
+        #[cfg(feature = "jit")]
+        self.jit.invalidate(addr, len);
+    }
+
+    fn write_u32(&mut self, offset: u32, value: u32) -> anyhow::Result<()> {
+        let eip = self.regs.eip;
+        self.mem
+            .write_u32(offset, value)
+            .map_err(|_| unmapped_at(eip))?;
+        self.invalidate(offset, 4);
+        Ok(())
+    }
+
+    pub fn read_u32(&self, offset: u32) -> anyhow::Result<u32> {
+        self.mem.read_u32(offset).map_err(|_| self.unmapped())
+    }
+
+    fn read_u8(&self, offset: u32) -> anyhow::Result<u8> {
+        self.mem.read_u8(offset).map_err(|_| self.unmapped())
+    }
+
+    fn read_u16(&self, offset: u32) -> anyhow::Result<u16> {
+        self.mem.read_u16(offset).map_err(|_| self.unmapped())
+    }
+
+    fn write_u8(&mut self, offset: u32, value: u8) -> anyhow::Result<()> {
+        let eip = self.regs.eip;
+        self.mem
+            .write_u8(offset, value)
+            .map_err(|_| unmapped_at(eip))?;
+        self.invalidate(offset, 1);
+        Ok(())
+    }
+
+    fn write_u16(&mut self, offset: u32, value: u16) -> anyhow::Result<()> {
+        let eip = self.regs.eip;
+        self.mem
+            .write_u16(offset, value)
+            .map_err(|_| unmapped_at(eip))?;
+        self.invalidate(offset, 2);
+        Ok(())
+    }
+
+    /// An unmapped-memory fault anchored at the current EIP.
+    fn unmapped(&self) -> anyhow::Error {
+        unmapped_at(self.regs.eip)
+    }
+
+    /// Read operand `n` as an `r/m32`, whether it names a register or memory.
+    fn rm32(&self, instr: &iced_x86::Instruction, n: u32) -> anyhow::Result<u32> {
+        match instr.op_kind(n) {
+            iced_x86::OpKind::Register => self.regs.get(instr.op_register(n)),
+            _ => self.read_u32(self.addr(instr)?),
         }
     }
 
-    fn write_u32(&mut self, offset: u32, value: u32) {
-        let offset = offset as usize;
-        self.mem[offset] = (value >> 0) as u8;
-        self.mem[offset + 1] = (value >> 8) as u8;
-        self.mem[offset + 2] = (value >> 16) as u8;
-        self.mem[offset + 3] = (value >> 24) as u8;
+    /// Read the `r/m8` (source) operand, register or memory.
+    fn rm8(&self, instr: &iced_x86::Instruction) -> anyhow::Result<u32> {
+        match instr.op1_kind() {
+            iced_x86::OpKind::Register => self.regs.get(instr.op1_register()),
+            _ => Ok(self.read_u8(self.addr(instr)?)? as u32),
+        }
     }
 
-    pub fn read_u32(&self, offset: u32) -> u32 {
-        let offset = offset as usize;
-        ((self.mem[offset] as u32) << 0)
-            | ((self.mem[offset + 1] as u32) << 8)
-            | ((self.mem[offset + 2] as u32) << 16)
-            | ((self.mem[offset + 3] as u32) << 24)
+    /// Read the `r/m16` (source) operand, register or memory.
+    fn rm16(&self, instr: &iced_x86::Instruction) -> anyhow::Result<u32> {
+        match instr.op1_kind() {
+            iced_x86::OpKind::Register => self.regs.get(instr.op1_register()),
+            _ => Ok(self.read_u16(self.addr(instr)?)? as u32),
+        }
     }
 
-    pub fn push(&mut self, value: u32) {
+    /// Write the `r/m8` (destination) operand, register or memory.
+    fn set_rm8(&mut self, instr: &iced_x86::Instruction, value: u32) -> anyhow::Result<()> {
+        match instr.op0_kind() {
+            iced_x86::OpKind::Register => self.regs.set(instr.op0_register(), value),
+            _ => self.write_u8(self.addr(instr)?, value as u8),
+        }
+    }
+
+    /// Write the `r/m16` (destination) operand, register or memory.
+    fn set_rm16(&mut self, instr: &iced_x86::Instruction, value: u32) -> anyhow::Result<()> {
+        match instr.op0_kind() {
+            iced_x86::OpKind::Register => self.regs.set(instr.op0_register(), value),
+            _ => self.write_u16(self.addr(instr)?, value as u16),
+        }
+    }
+
+    /// Write the `r/m32` (destination) operand, register or memory.
+    fn set_rm32(&mut self, instr: &iced_x86::Instruction, value: u32) -> anyhow::Result<()> {
+        match instr.op0_kind() {
+            iced_x86::OpKind::Register => self.regs.set(instr.op0_register(), value),
+            _ => self.write_u32(self.addr(instr)?, value),
+        }
+    }
+
+    pub fn push(&mut self, value: u32) -> anyhow::Result<()> {
         self.regs.esp -= 4;
-        self.write_u32(self.regs.esp, value);
+        self.write_u32(self.regs.esp, value)
     }
 
-    pub fn pop(&mut self) -> u32 {
-        let value = self.read_u32(self.regs.esp);
+    pub fn pop(&mut self) -> anyhow::Result<u32> {
+        let value = self.read_u32(self.regs.esp)?;
         self.regs.esp += 4;
-        value
+        Ok(value)
     }
 
     /// Compute the address found in instructions that reference memory, e.g.
     ///   mov [eax+03h],...
-    fn addr(&self, instr: &iced_x86::Instruction) -> u32 {
+    fn addr(&self, instr: &iced_x86::Instruction) -> anyhow::Result<u32> {
         assert!(instr.memory_index_scale() == 1);
-        self.regs.get(instr.memory_index()) + instr.memory_displacement32()
+        Ok(self.regs.get(instr.memory_index())? + instr.memory_displacement32())
+    }
+
+    /// Replace the arithmetic status bits of EFLAGS with a freshly computed set.
+    fn set_flags(&mut self, flags: u32) {
+        self.regs.eflags = (self.regs.eflags & !Flags::ARITH) | flags;
+    }
+
+    /// Evaluate a condition code (as used by `Jcc`/`Setcc`) against EFLAGS.
+    fn cond(&self, cc: iced_x86::ConditionCode) -> bool {
+        use iced_x86::ConditionCode as CC;
+        let cf = self.regs.flag(Flags::CF);
+        let zf = self.regs.flag(Flags::ZF);
+        let sf = self.regs.flag(Flags::SF);
+        let of = self.regs.flag(Flags::OF);
+        let pf = self.regs.flag(Flags::PF);
+        match cc {
+            CC::None => true,
+            CC::o => of,
+            CC::no => !of,
+            CC::b => cf,
+            CC::ae => !cf,
+            CC::e => zf,
+            CC::ne => !zf,
+            CC::be => cf || zf,
+            CC::a => !cf && !zf,
+            CC::s => sf,
+            CC::ns => !sf,
+            CC::p => pf,
+            CC::np => !pf,
+            CC::l => sf != of,
+            CC::ge => sf == of,
+            CC::le => zf || sf != of,
+            CC::g => !zf && sf == of,
+        }
     }
 
     fn run(&mut self, instr: &iced_x86::Instruction) -> anyhow::Result<()> {
         self.regs.eip = instr.next_ip() as u32;
+
+        // Conditional branches decode their condition generically rather than
+        // enumerating the 16 variants as `Code` arms.
+        if instr.is_jcc_short_or_near() {
+            if self.cond(instr.condition_code()) {
+                self.regs.eip = instr.near_branch32();
+            }
+            return Ok(());
+        }
+
         match instr.code() {
             iced_x86::Code::Enterd_imm16_imm8 => {
-                self.push(self.regs.ebp);
+                self.push(self.regs.ebp)?;
                 self.regs.ebp = self.regs.esp;
                 self.regs.esp -= instr.immediate16() as u32;
             }
 
             iced_x86::Code::Call_rel32_32 => {
-                self.push(self.regs.eip);
+                self.push(self.regs.eip)?;
                 self.regs.eip = instr.near_branch32();
             }
             iced_x86::Code::Call_rm32 => {
                 // call dword ptr [addr]
-                assert!(instr.memory_index() == iced_x86::Register::None);
-                let target = self.read_u32(self.addr(instr));
+                let target = self.read_u32(self.addr(instr)?)?;
                 match self.imports.get(&target) {
                     Some(handler) => match handler {
                         Some(handler) => handler(self),
-                        None => log::error!("unimplemented import: {:x}", target),
+                        None => {
+                            return Err(Fault {
+                                kind: FaultKind::UnimplementedImport(target),
+                                eip: instr.ip() as u32,
+                            }
+                            .into())
+                        }
                     },
                     None => {
-                        self.push(self.regs.eip);
+                        self.push(self.regs.eip)?;
                         self.regs.eip = target;
                     }
                 };
             }
-            iced_x86::Code::Retnd => self.regs.eip = self.pop(),
+            iced_x86::Code::Retnd => self.regs.eip = self.pop()?,
 
             iced_x86::Code::Jmp_rel32_32 => {
                 self.regs.eip = instr.near_branch32();
             }
 
-            iced_x86::Code::Pushd_imm8 => self.push(instr.immediate8to32() as u32),
-            iced_x86::Code::Pushd_imm32 => self.push(instr.immediate32()),
-            iced_x86::Code::Push_r32 => self.push(self.regs.get(instr.op0_register())),
+            iced_x86::Code::Pushd_imm8 => self.push(instr.immediate8to32() as u32)?,
+            iced_x86::Code::Pushd_imm32 => self.push(instr.immediate32())?,
+            iced_x86::Code::Push_r32 => {
+                let value = self.regs.get(instr.op0_register())?;
+                self.push(value)?;
+            }
             iced_x86::Code::Push_rm32 => {
                 // push [eax+10h]
-                let value = self
-                    .read_u32(self.addr(instr));
-                self.push(value);
+                let value = self.read_u32(self.addr(instr)?)?;
+                self.push(value)?;
             }
 
             iced_x86::Code::Pop_r32 => {
-                let value = self.pop();
-                self.regs.set(instr.op0_register(), value);
+                let value = self.pop()?;
+                self.regs.set(instr.op0_register(), value)?;
             }
 
             iced_x86::Code::Mov_rm32_imm32 => {
                 // mov dword ptr [x], y
-                self.write_u32(self.addr(instr), instr.immediate32());
+                self.write_u32(self.addr(instr)?, instr.immediate32())?;
             }
             iced_x86::Code::Mov_moffs32_EAX => {
                 // mov [x],eax
-                self.write_u32(self.addr(instr), self.regs.eax);
+                self.write_u32(self.addr(instr)?, self.regs.eax)?;
             }
             iced_x86::Code::Mov_EAX_moffs32 => {
                 // mov eax,[x]
-                self.regs.eax = self.read_u32(self.addr(instr));
+                self.regs.eax = self.read_u32(self.addr(instr)?)?;
             }
             iced_x86::Code::Mov_rm32_r32 => {
-                assert!(instr.op0_kind() == iced_x86::OpKind::Register);
-                self.regs
-                    .set(instr.op0_register(), self.regs.get(instr.op1_register()));
+                let value = self.regs.get(instr.op1_register())?;
+                self.set_rm32(instr, value)?;
             }
             iced_x86::Code::Mov_r32_rm32 => {
-                assert!(instr.op1_kind() == iced_x86::OpKind::Register);
-                self.regs
-                    .set(instr.op0_register(), self.regs.get(instr.op1_register()));
+                let value = self.rm32(instr, 1)?;
+                self.regs.set(instr.op0_register(), value)?;
             }
 
             iced_x86::Code::And_rm32_imm8 => {
-                assert!(instr.op0_kind() == iced_x86::OpKind::Register);
-                let reg = instr.op0_register();
-                self.regs
-                    .set(reg, self.regs.get(reg) & instr.immediate8() as u32);
+                let result = self.rm32(instr, 0)? & instr.immediate8to32() as u32;
+                self.set_flags(Flags::logic(result, Size::Long));
+                self.set_rm32(instr, result)?;
             }
 
             iced_x86::Code::Sub_rm32_imm32 => {
-                assert!(instr.op0_kind() == iced_x86::OpKind::Register);
-                let reg = instr.op0_register();
-                self.regs.set(reg, self.regs.get(reg) - instr.immediate32());
+                let (result, flags) =
+                    Flags::sub(self.rm32(instr, 0)?, instr.immediate32(), Size::Long);
+                self.set_flags(flags);
+                self.set_rm32(instr, result)?;
+            }
+
+            iced_x86::Code::Add_rm32_imm32 => {
+                let (result, flags) =
+                    Flags::add(self.rm32(instr, 0)?, instr.immediate32(), Size::Long);
+                self.set_flags(flags);
+                self.set_rm32(instr, result)?;
+            }
+            iced_x86::Code::Add_rm32_imm8 => {
+                let (result, flags) =
+                    Flags::add(self.rm32(instr, 0)?, instr.immediate8to32() as u32, Size::Long);
+                self.set_flags(flags);
+                self.set_rm32(instr, result)?;
+            }
+            iced_x86::Code::Add_rm32_r32 => {
+                let (result, flags) = Flags::add(
+                    self.rm32(instr, 0)?,
+                    self.regs.get(instr.op1_register())?,
+                    Size::Long,
+                );
+                self.set_flags(flags);
+                self.set_rm32(instr, result)?;
+            }
+            iced_x86::Code::Add_r32_rm32 => {
+                let (result, flags) = Flags::add(
+                    self.regs.get(instr.op0_register())?,
+                    self.rm32(instr, 1)?,
+                    Size::Long,
+                );
+                self.set_flags(flags);
+                self.regs.set(instr.op0_register(), result)?;
+            }
+
+            iced_x86::Code::Cmp_rm32_imm32 => {
+                let (_, flags) = Flags::sub(self.rm32(instr, 0)?, instr.immediate32(), Size::Long);
+                self.set_flags(flags);
+            }
+            iced_x86::Code::Cmp_rm32_imm8 => {
+                let (_, flags) =
+                    Flags::sub(self.rm32(instr, 0)?, instr.immediate8to32() as u32, Size::Long);
+                self.set_flags(flags);
+            }
+            iced_x86::Code::Cmp_rm32_r32 => {
+                let (_, flags) = Flags::sub(
+                    self.rm32(instr, 0)?,
+                    self.regs.get(instr.op1_register())?,
+                    Size::Long,
+                );
+                self.set_flags(flags);
+            }
+            iced_x86::Code::Cmp_r32_rm32 => {
+                let (_, flags) = Flags::sub(
+                    self.regs.get(instr.op0_register())?,
+                    self.rm32(instr, 1)?,
+                    Size::Long,
+                );
+                self.set_flags(flags);
+            }
+
+            iced_x86::Code::Test_rm32_imm32 => {
+                let result = self.rm32(instr, 0)? & instr.immediate32();
+                self.set_flags(Flags::logic(result, Size::Long));
+            }
+            iced_x86::Code::Test_rm32_r32 => {
+                let result = self.rm32(instr, 0)? & self.regs.get(instr.op1_register())?;
+                self.set_flags(Flags::logic(result, Size::Long));
+            }
+
+            iced_x86::Code::Movzx_r32_rm8 => {
+                let value = self.rm8(instr)?;
+                self.regs
+                    .set(instr.op0_register(), value & Size::Byte.mask())?;
+            }
+            iced_x86::Code::Movzx_r32_rm16 => {
+                let value = self.rm16(instr)?;
+                self.regs
+                    .set(instr.op0_register(), value & Size::Word.mask())?;
+            }
+            iced_x86::Code::Movsx_r32_rm8 => {
+                let value = self.rm8(instr)? as u8 as i8 as i32 as u32;
+                self.regs.set(instr.op0_register(), value)?;
+            }
+            iced_x86::Code::Movsx_r32_rm16 => {
+                let value = self.rm16(instr)? as u16 as i16 as i32 as u32;
+                self.regs.set(instr.op0_register(), value)?;
+            }
+
+            iced_x86::Code::Mov_r8_rm8 | iced_x86::Code::Mov_r16_rm16 => {
+                let value = if instr.code() == iced_x86::Code::Mov_r8_rm8 {
+                    self.rm8(instr)?
+                } else {
+                    self.rm16(instr)?
+                };
+                self.regs.set(instr.op0_register(), value)?;
+            }
+            iced_x86::Code::Mov_rm8_r8 => {
+                let value = self.regs.get(instr.op1_register())?;
+                self.set_rm8(instr, value)?;
+            }
+            iced_x86::Code::Mov_rm16_r16 => {
+                let value = self.regs.get(instr.op1_register())?;
+                self.set_rm16(instr, value)?;
+            }
+            iced_x86::Code::Mov_r8_imm8 => {
+                self.regs
+                    .set(instr.op0_register(), instr.immediate8() as u32)?;
+            }
+            iced_x86::Code::Mov_rm8_imm8 => {
+                self.set_rm8(instr, instr.immediate8() as u32)?;
+            }
+
+            iced_x86::Code::Xor_rm8_r8 => {
+                let dst = match instr.op0_kind() {
+                    iced_x86::OpKind::Register => self.regs.get(instr.op0_register())?,
+                    _ => self.read_u8(self.addr(instr)?)? as u32,
+                };
+                let result = dst ^ self.regs.get(instr.op1_register())?;
+                self.set_flags(Flags::logic(result, Size::Byte));
+                self.set_rm8(instr, result)?;
+            }
+
+            iced_x86::Code::Setb_rm8
+            | iced_x86::Code::Setae_rm8
+            | iced_x86::Code::Sete_rm8
+            | iced_x86::Code::Setne_rm8
+            | iced_x86::Code::Setbe_rm8
+            | iced_x86::Code::Seta_rm8
+            | iced_x86::Code::Sets_rm8
+            | iced_x86::Code::Setns_rm8
+            | iced_x86::Code::Setp_rm8
+            | iced_x86::Code::Setnp_rm8
+            | iced_x86::Code::Setl_rm8
+            | iced_x86::Code::Setge_rm8
+            | iced_x86::Code::Setle_rm8
+            | iced_x86::Code::Setg_rm8
+            | iced_x86::Code::Seto_rm8
+            | iced_x86::Code::Setno_rm8 => {
+                let value = self.cond(instr.condition_code()) as u8;
+                match instr.op0_kind() {
+                    iced_x86::OpKind::Register => {
+                        let reg = instr.op0_register();
+                        let full = self.regs.get(reg)?;
+                        self.regs.set(reg, (full & !0xff) | value as u32)?;
+                    }
+                    _ => self.write_u8(self.addr(instr)?, value)?,
+                }
             }
 
             iced_x86::Code::Lea_r32_m => {
                 // lea eax,[esp+10h]
-                self.regs.set(instr.op0_register(), self.addr(instr));
+                let addr = self.addr(instr)?;
+                self.regs.set(instr.op0_register(), addr)?;
             }
 
-            code => {
+            _ => {
                 self.regs.eip -= instr.len() as u32;
-                bail!("unhandled instruction {:?}", code);
+                return Err(Fault {
+                    kind: FaultKind::IllegalInstruction,
+                    eip: self.regs.eip,
+                }
+                .into());
             }
         }
         Ok(())
     }
 
+    /// Decode the instruction at `eip`, memoizing the result so repeated visits
+    /// (tight loops) skip reconstructing the decoder.
+    fn decode(&mut self, eip: u32) -> anyhow::Result<iced_x86::Instruction> {
+        if let Some(instr) = self.decode_cache.get(&eip) {
+            return Ok(*instr);
+        }
+        let code = self.mem.code(eip).map_err(|_| unmapped_at(eip))?;
+        let mut decoder =
+            iced_x86::Decoder::with_ip(32, code, eip as u64, iced_x86::DecoderOptions::NONE);
+        let instr = decoder.decode();
+        self.decode_cache.insert(eip, instr);
+        Ok(instr)
+    }
+
     pub fn step(&mut self) -> anyhow::Result<()> {
-        let mut decoder = iced_x86::Decoder::with_ip(
-            32,
-            &self.mem[self.regs.eip as usize..],
-            self.regs.eip as u64,
-            iced_x86::DecoderOptions::NONE,
-        );
-        self.run(&decoder.decode())
+        if self.state == State::Halted {
+            return Ok(());
+        }
+        self.state = State::Running;
+
+        // Fast path: run a compiled block if one covers the current EIP. The JIT
+        // only emits code for opcodes it fully supports and leaves EIP at the
+        // first instruction it could not translate, so the next `step` hands
+        // that instruction to the interpreter below — the two modes stay
+        // behaviorally identical.
+        #[cfg(feature = "jit")]
+        if jit::run_block(self) {
+            return Ok(());
+        }
+
+        let eip = self.regs.eip;
+        let result = self.decode(eip).and_then(|instr| self.run(&instr));
+        match result {
+            Ok(()) => Ok(()),
+            // Route faults through the dispatch hook, anchored at the faulting
+            // instruction; anything else halts too.
+            Err(err) => match err.downcast::<Fault>() {
+                Ok(fault) => self.dispatch(Fault { eip, ..fault }),
+                Err(err) => {
+                    self.state = State::Halted;
+                    Err(err)
+                }
+            },
+        }
+    }
+}
+
+/// An optional basic-block JIT that translates a run of guest x86-32
+/// instructions into native host x86-64 code, in the spirit of `juicebox-asm`
+/// (a flat code buffer with REX/ModR/M encoders and bindable labels).
+///
+/// This is an initial backend: it only emits code for the register-only,
+/// flag-neutral handlers and stops a block at the first opcode it cannot
+/// translate, so that `step` falls back to the interpreter and the two
+/// execution modes stay behaviorally identical. The encoder scaffolding
+/// (labels, branch emitters) is in place for the opcodes still to come.
+#[cfg(feature = "jit")]
+mod jit {
+    // Encoder scaffolding ahead of the opcodes that will use it.
+    #![allow(dead_code)]
+
+    use std::collections::HashMap;
+    use std::ffi::c_void;
+
+    use super::X86;
+
+    /// Host x86-64 general-purpose registers (low eight).
+    #[derive(Clone, Copy)]
+    enum Reg {
+        Rax = 0,
+        Rcx = 1,
+        Rdx = 2,
+        Rbx = 3,
+        Rsp = 4,
+        Rbp = 5,
+        Rsi = 6,
+        Rdi = 7,
+    }
+
+    /// A jump target in the emitted code: either already bound to an offset, or
+    /// pending with a list of rel32 operand sites to patch once bound.
+    struct Label {
+        bound: Option<usize>,
+        patches: Vec<usize>,
+    }
+    impl Label {
+        fn new() -> Self {
+            Label {
+                bound: None,
+                patches: Vec::new(),
+            }
+        }
+    }
+
+    /// A growable buffer of host machine code with just enough encoders to
+    /// materialize guest register loads/stores against the `X86` struct.
+    struct Assembler {
+        code: Vec<u8>,
+    }
+    impl Assembler {
+        fn new() -> Self {
+            Assembler { code: Vec::new() }
+        }
+
+        fn emit(&mut self, byte: u8) {
+            self.code.push(byte);
+        }
+        fn emit_u32(&mut self, value: u32) {
+            self.code.extend_from_slice(&value.to_le_bytes());
+        }
+
+        /// ModR/M byte for `[rdi + disp32]` addressing with `reg` in the reg
+        /// field (mod=10, rm=111 = rdi).
+        fn modrm_rdi_disp(&mut self, reg: Reg) {
+            self.emit(0b10_000_111 | ((reg as u8) << 3));
+        }
+
+        /// `mov reg, dword [rdi + disp]`
+        fn load(&mut self, reg: Reg, disp: i32) {
+            self.emit(0x8b);
+            self.modrm_rdi_disp(reg);
+            self.emit_u32(disp as u32);
+        }
+        /// `mov dword [rdi + disp], reg`
+        fn store(&mut self, disp: i32, reg: Reg) {
+            self.emit(0x89);
+            self.modrm_rdi_disp(reg);
+            self.emit_u32(disp as u32);
+        }
+        /// `mov dword [rdi + disp], imm32`
+        fn store_imm(&mut self, disp: i32, imm: u32) {
+            self.emit(0xc7);
+            self.modrm_rdi_disp(Reg::Rax); // reg field is the /0 opcode extension
+            self.emit_u32(disp as u32);
+            self.emit_u32(imm);
+        }
+        fn ret(&mut self) {
+            self.emit(0xc3);
+        }
+
+        /// Emit an unconditional `jmp rel32` to `label`, recording a patch site
+        /// if the label is not yet bound.
+        fn jmp(&mut self, label: &mut Label) {
+            self.emit(0xe9);
+            let site = self.code.len();
+            self.emit_u32(0);
+            match label.bound {
+                Some(target) => {
+                    let rel = (target as i64 - (site as i64 + 4)) as u32;
+                    self.code[site..site + 4].copy_from_slice(&rel.to_le_bytes());
+                }
+                None => label.patches.push(site),
+            }
+        }
+
+        /// Bind `label` to the current position, resolving pending rel32 sites.
+        fn bind(&mut self, label: &mut Label) {
+            let target = self.code.len();
+            label.bound = Some(target);
+            for &site in &label.patches {
+                let rel = (target as i64 - (site as i64 + 4)) as u32;
+                self.code[site..site + 4].copy_from_slice(&rel.to_le_bytes());
+            }
+            label.patches.clear();
+        }
+    }
+
+    // --- host executable memory (unix) ---
+
+    const PROT_READ: i32 = 0x1;
+    const PROT_WRITE: i32 = 0x2;
+    const PROT_EXEC: i32 = 0x4;
+    const MAP_PRIVATE: i32 = 0x2;
+    const MAP_ANONYMOUS: i32 = 0x20;
+
+    extern "C" {
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: i32,
+            flags: i32,
+            fd: i32,
+            offset: i64,
+        ) -> *mut c_void;
+        fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+        fn munmap(addr: *mut c_void, len: usize) -> i32;
+    }
+
+    /// A page of host memory holding compiled code, flipped from writable to
+    /// executable once the bytes are in place (W^X).
+    struct ExecBuffer {
+        ptr: *mut u8,
+        len: usize,
+    }
+    impl ExecBuffer {
+        fn new(code: &[u8]) -> Option<Self> {
+            let len = code.len();
+            if len == 0 {
+                return None;
+            }
+            // SAFETY: standard anonymous mmap; we check for MAP_FAILED below.
+            let ptr = unsafe {
+                mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    PROT_READ | PROT_WRITE,
+                    MAP_PRIVATE | MAP_ANONYMOUS,
+                    -1,
+                    0,
+                )
+            };
+            if ptr == usize::MAX as *mut c_void {
+                return None;
+            }
+            let ptr = ptr as *mut u8;
+            // SAFETY: `ptr` owns `len` freshly mapped, writable bytes.
+            unsafe {
+                std::ptr::copy_nonoverlapping(code.as_ptr(), ptr, len);
+                if mprotect(ptr as *mut c_void, len, PROT_READ | PROT_EXEC) != 0 {
+                    munmap(ptr as *mut c_void, len);
+                    return None;
+                }
+            }
+            Some(ExecBuffer { ptr, len })
+        }
+
+        fn func(&self) -> extern "C" fn(*mut X86) {
+            // SAFETY: the buffer holds a complete block ending in `ret` and the
+            // emitted code only touches fields reachable from the `X86` pointer.
+            unsafe { std::mem::transmute::<*mut u8, extern "C" fn(*mut X86)>(self.ptr) }
+        }
+    }
+    impl Drop for ExecBuffer {
+        fn drop(&mut self) {
+            // SAFETY: `ptr`/`len` come from our own successful `mmap`.
+            unsafe {
+                munmap(self.ptr as *mut c_void, self.len);
+            }
+        }
+    }
+
+    /// A compiled block and the guest range `[start, end)` it was built from.
+    struct CompiledBlock {
+        exec: ExecBuffer,
+        start: u32,
+        end: u32,
+    }
+
+    /// Compiled blocks keyed by the guest EIP they begin at.
+    pub struct JitCache {
+        blocks: HashMap<u32, CompiledBlock>,
+    }
+    impl JitCache {
+        pub fn new() -> Self {
+            JitCache {
+                blocks: HashMap::new(),
+            }
+        }
+
+        /// Drop compiled blocks overlapping `[addr, addr + len)` so writes into
+        /// a block's source range recompile it.
+        pub fn invalidate(&mut self, addr: u32, len: u32) {
+            let end = addr + len;
+            self.blocks
+                .retain(|_, b| b.end <= addr || b.start >= end);
+        }
+    }
+
+    /// Displacement of a guest 32-bit register, relative to the `X86` base
+    /// pointer passed to compiled blocks (layout-independent: measured from the
+    /// live instance rather than assumed).
+    fn reg_disp(cpu: &X86, reg: iced_x86::Register) -> Option<i32> {
+        use iced_x86::Register as R;
+        let field: *const u32 = match reg {
+            R::EAX => &cpu.regs.eax,
+            R::EBX => &cpu.regs.ebx,
+            R::ECX => &cpu.regs.ecx,
+            R::EDX => &cpu.regs.edx,
+            R::ESP => &cpu.regs.esp,
+            R::EBP => &cpu.regs.ebp,
+            R::ESI => &cpu.regs.esi,
+            R::EDI => &cpu.regs.edi,
+            _ => return None,
+        };
+        Some((field as usize - cpu as *const X86 as usize) as i32)
+    }
+
+    fn eip_disp(cpu: &X86) -> i32 {
+        (&cpu.regs.eip as *const u32 as usize - cpu as *const X86 as usize) as i32
+    }
+
+    /// Translate a register-only `mov` into a load/store through scratch EAX.
+    fn emit_reg_mov(asm: &mut Assembler, cpu: &X86, dst: iced_x86::Register, src: iced_x86::Register) -> bool {
+        match (reg_disp(cpu, dst), reg_disp(cpu, src)) {
+            (Some(dst), Some(src)) => {
+                asm.load(Reg::Rax, src);
+                asm.store(dst, Reg::Rax);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Compile a basic block starting at `eip`, or `None` if the very first
+    /// instruction is not translatable.
+    fn compile(cpu: &X86, eip: u32) -> Option<CompiledBlock> {
+        let bytes = cpu.mem.code(eip).ok()?;
+        let mut decoder =
+            iced_x86::Decoder::with_ip(32, bytes, eip as u64, iced_x86::DecoderOptions::NONE);
+
+        let mut asm = Assembler::new();
+        let mut end = eip;
+        loop {
+            if !decoder.can_decode() {
+                break;
+            }
+            let instr = decoder.decode();
+            let covered = match instr.code() {
+                iced_x86::Code::Mov_rm32_r32 | iced_x86::Code::Mov_r32_rm32
+                    if instr.op0_kind() == iced_x86::OpKind::Register
+                        && instr.op1_kind() == iced_x86::OpKind::Register =>
+                {
+                    emit_reg_mov(&mut asm, cpu, instr.op0_register(), instr.op1_register())
+                }
+                _ => false,
+            };
+            if !covered {
+                break;
+            }
+            end = instr.next_ip() as u32;
+        }
+
+        if end == eip {
+            return None;
+        }
+        asm.store_imm(eip_disp(cpu), end);
+        asm.ret();
+        let exec = ExecBuffer::new(&asm.code)?;
+        Some(CompiledBlock { exec, start: eip, end })
+    }
+
+    /// Run a compiled block covering the current EIP, compiling one on demand.
+    /// Returns `false` when nothing could be translated, leaving EIP untouched
+    /// for the interpreter.
+    pub fn run_block(cpu: &mut X86) -> bool {
+        let eip = cpu.regs.eip;
+        if !cpu.jit.blocks.contains_key(&eip) {
+            match compile(cpu, eip) {
+                Some(block) => {
+                    cpu.jit.blocks.insert(eip, block);
+                }
+                None => return false,
+            }
+        }
+        let func = cpu.jit.blocks[&eip].exec.func();
+        func(cpu as *mut X86);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Flags, Registers, Size};
+
+    #[test]
+    fn sub_flags() {
+        // Equal operands: ZF, no CF/OF/SF.
+        let (result, flags) = Flags::sub(5, 5, Size::Long);
+        assert_eq!(result, 0);
+        assert_eq!(flags & Flags::ZF, Flags::ZF);
+        assert_eq!(flags & (Flags::CF | Flags::OF | Flags::SF), 0);
+
+        // Unsigned borrow: 0 - 1 sets CF and SF (result 0xffffffff), no OF.
+        let (result, flags) = Flags::sub(0, 1, Size::Long);
+        assert_eq!(result, 0xffff_ffff);
+        assert_eq!(flags & Flags::CF, Flags::CF);
+        assert_eq!(flags & Flags::SF, Flags::SF);
+        assert_eq!(flags & Flags::OF, 0);
+
+        // Signed overflow: INT_MIN - 1 wraps to INT_MAX, sets OF, clears CF.
+        let (result, flags) = Flags::sub(0x8000_0000, 1, Size::Long);
+        assert_eq!(result, 0x7fff_ffff);
+        assert_eq!(flags & Flags::OF, Flags::OF);
+        assert_eq!(flags & Flags::CF, 0);
+    }
+
+    #[test]
+    fn add_flags() {
+        // Signed overflow: INT_MAX + 1 wraps to INT_MIN, sets OF and SF, no CF.
+        let (result, flags) = Flags::add(0x7fff_ffff, 1, Size::Long);
+        assert_eq!(result, 0x8000_0000);
+        assert_eq!(flags & Flags::OF, Flags::OF);
+        assert_eq!(flags & Flags::SF, Flags::SF);
+        assert_eq!(flags & Flags::CF, 0);
+
+        // Unsigned carry out, no signed overflow.
+        let (result, flags) = Flags::add(0xffff_ffff, 1, Size::Long);
+        assert_eq!(result, 0);
+        assert_eq!(flags & Flags::CF, Flags::CF);
+        assert_eq!(flags & Flags::ZF, Flags::ZF);
+        assert_eq!(flags & Flags::OF, 0);
+    }
+
+    #[test]
+    fn logic_flags() {
+        // CF and OF are always cleared by bitwise ops.
+        let flags = Flags::logic(0x3, Size::Long);
+        assert_eq!(flags & (Flags::CF | Flags::OF), 0);
+        // 0b11 has even parity.
+        assert_eq!(flags & Flags::PF, Flags::PF);
+        assert_eq!(flags & Flags::ZF, 0);
+
+        // 0b1 has odd parity, so PF is clear.
+        let flags = Flags::logic(0x1, Size::Long);
+        assert_eq!(flags & Flags::PF, 0);
+    }
+
+    #[test]
+    fn part_splicing() {
+        let backing = 0x1122_3344;
+        assert_eq!(Registers::read_part(backing, Size::Byte, false), 0x44); // AL
+        assert_eq!(Registers::read_part(backing, Size::Byte, true), 0x33); // AH
+        assert_eq!(Registers::read_part(backing, Size::Word, false), 0x3344); // AX
+
+        // Writing AH must not disturb AL or the high word.
+        let spliced = Registers::write_part(backing, Size::Byte, true, 0xaa);
+        assert_eq!(spliced, 0x1122_aa44);
+
+        // Writing AL must not disturb AH or the high word.
+        let spliced = Registers::write_part(backing, Size::Byte, false, 0xaa);
+        assert_eq!(spliced, 0x1122_33aa);
     }
 }